@@ -0,0 +1,67 @@
+extern crate ffmpeg_next as ffmpeg;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ffmpeg::format::Pixel;
+use libav_frame_encoder::{encoder::frame_from_video_plane, sink::{RetroAVCollector, VideoPlane}};
+
+const WIDTH: usize = 1920;
+const HEIGHT: usize = 1080;
+const BYTES_PER_PIXEL: usize = 4;
+
+fn on_video_refresh(c: &mut Criterion) {
+    let data = vec![0x80u8; WIDTH * HEIGHT * BYTES_PER_PIXEL];
+    let mut collector = RetroAVCollector::new();
+
+    let mut group = c.benchmark_group("on_video_refresh");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("1920x1080 bgra", |b| {
+        let mut frame_number = 0;
+        b.iter(|| {
+            collector.on_video_refresh(black_box(&data), WIDTH as u32, HEIGHT as u32, (WIDTH * BYTES_PER_PIXEL) as u32, frame_number).unwrap();
+            frame_number += 1;
+            // keep the channel from growing for the length of the benchmark
+            collector.sink.output.try_recv().unwrap();
+        })
+    });
+    group.finish();
+}
+
+fn copy_video_plane(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame_from_video_plane");
+    group.throughput(Throughput::Bytes((WIDTH * HEIGHT * BYTES_PER_PIXEL) as u64));
+    // a pitch matching ffmpeg's stride takes the single copy path, padded ones get copied row by row
+    for padding in [0, 64, 256].iter() {
+        let pitch = WIDTH * BYTES_PER_PIXEL + padding;
+        let plane = VideoPlane {
+            data: vec![0x80; pitch * HEIGHT].into(),
+            width: WIDTH,
+            height: HEIGHT,
+            pitch,
+            pixel_format: Some(Pixel::BGRA),
+        };
+        group.bench_with_input(BenchmarkId::new("pitch", pitch), &plane, |b, plane| {
+            b.iter(|| frame_from_video_plane(black_box(plane), Pixel::BGRA))
+        });
+    }
+    group.finish();
+}
+
+fn on_audio_sample_batch(c: &mut Criterion) {
+    let samples = vec![0i16; 2048 * 2];
+    let mut collector = RetroAVCollector::new();
+
+    let mut group = c.benchmark_group("on_audio_sample_batch");
+    group.throughput(Throughput::Elements(2048));
+    group.bench_function("2048 stereo samples", |b| {
+        let mut frame_number = 0;
+        b.iter(|| {
+            collector.on_audio_sample_batch(black_box(&samples), frame_number);
+            frame_number += 1;
+            collector.sink.output.try_recv().unwrap();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, on_video_refresh, copy_video_plane, on_audio_sample_batch);
+criterion_main!(benches);
@@ -0,0 +1,191 @@
+extern crate ffmpeg_next as ffmpeg;
+
+use std::{fmt, path::PathBuf};
+
+use ffmpeg::format::Pixel;
+
+use crate::encoder::OutputArgs;
+
+#[derive(Debug)]
+pub enum EncodeError {
+    FfmpegError(ffmpeg::Error),
+    InvalidArgs(String),
+    Io { path: PathBuf, source: std::io::Error },
+    ResourceExhausted,
+    NetworkError { url: String, source: ffmpeg::Error },
+    VersionMismatch { required: String, found: String },
+    Filter(FilterError),
+    InsufficientDiskSpace { available_bytes: u64, estimated_bytes: u64 },
+    MissingContext,
+    MissingVideoStream,
+    MissingAudioStream,
+    MissingSubtitleStream,
+    AlreadyConfigured { existing: Box<OutputArgs>, incoming: Box<OutputArgs> },
+    Aborted,
+    InvalidFrame(ValidationError),
+    // a codec feature of this crate is enabled, but the ffmpeg it's running against doesn't have the encoder
+    FeatureNotCompiledIn(String),
+    // a filter graph that was working couldn't be put back together after changing it
+    FilterReconfigurationFailed { filter_name: String, reason: String },
+    CodecFormatMismatch { codec: String, expected: String, got: String },
+    IncompatibleCodecAndContainer { codec: String, container: String },
+    // ffmpeg has no name for it, e.g. Pixel::None
+    InvalidPixelFormat(Pixel),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::FfmpegError(e) => write!(f, "ffmpeg error: {}", e),
+            EncodeError::InvalidArgs(reason) => write!(f, "invalid arguments: {}", reason),
+            EncodeError::Io { path, source } => write!(f, "IO error for {:?}: {}", path, source),
+            EncodeError::ResourceExhausted => write!(f, "ffmpeg ran out of memory"),
+            EncodeError::NetworkError { url, source } => write!(f, "network error for {}: {}", url, source),
+            EncodeError::VersionMismatch { required, found } => write!(f, "ffmpeg {} or newer is required, found {}", required, found),
+            EncodeError::Filter(e) => write!(f, "filter error: {}", e),
+            EncodeError::InsufficientDiskSpace { available_bytes, estimated_bytes } => {
+                write!(f, "not enough disk space: {} bytes available, about {} bytes needed", available_bytes, estimated_bytes)
+            },
+            EncodeError::MissingContext => write!(f, "tried to encode before the encoder was configured"),
+            EncodeError::MissingVideoStream => write!(f, "tried to encode video, but the output has no video stream"),
+            EncodeError::MissingAudioStream => write!(f, "tried to encode audio, but the output has no audio stream"),
+            EncodeError::MissingSubtitleStream => write!(f, "tried to encode a subtitle, but the output has no subtitle stream"),
+            EncodeError::AlreadyConfigured { existing, incoming } => {
+                write!(f, "already configured with {:?}, can't switch to {:?}", existing, incoming)
+            },
+            EncodeError::Aborted => write!(f, "encoding was aborted"),
+            EncodeError::InvalidFrame(e) => write!(f, "invalid frame: {}", e),
+            EncodeError::FilterReconfigurationFailed { filter_name, reason } => write!(f, "couldn't reconfigure the {} filter graph: {}", filter_name, reason),
+            EncodeError::CodecFormatMismatch { codec, expected, got } => {
+                write!(f, "{} expects pixel format {}, but the filters produce {}", codec, expected, got)
+            },
+            EncodeError::IncompatibleCodecAndContainer { codec, container } => write!(f, "{} can't be stored in a {} container", codec, container),
+            EncodeError::InvalidPixelFormat(format) => write!(f, "ffmpeg doesn't recognise pixel format {:?}", format),
+            EncodeError::FeatureNotCompiledIn(feature) => write!(f, "the {} feature is enabled, but ffmpeg was built without that encoder", feature),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EncodeError::FfmpegError(e) => Some(e),
+            EncodeError::Io { source, .. } => Some(source),
+            EncodeError::NetworkError { source, .. } => Some(source),
+            EncodeError::Filter(e) => Some(e),
+            EncodeError::InvalidFrame(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ffmpeg::Error> for EncodeError {
+    fn from(e: ffmpeg::Error) -> Self {
+        EncodeError::FfmpegError(e)
+    }
+}
+
+// Which step of building a filter graph failed
+#[derive(Debug)]
+pub enum FilterError {
+    FilterNotFound(&'static str),
+    AddFilter { name: String, source: ffmpeg::Error },
+    // connecting the graph to its buffer source/sink pads
+    Link { pad: String, source: ffmpeg::Error },
+    Parse { spec: String, source: ffmpeg::Error },
+    Validate { source: ffmpeg::Error, graph_dump: String },
+}
+
+impl FilterError {
+    pub fn add_filter(name: &str, e: ffmpeg::Error) -> Self {
+        FilterError::AddFilter { name: name.to_string(), source: e }
+    }
+
+    pub fn link(pad: &str, e: ffmpeg::Error) -> Self {
+        FilterError::Link { pad: pad.to_string(), source: e }
+    }
+
+    pub fn parse(spec: &str, e: ffmpeg::Error) -> Self {
+        FilterError::Parse { spec: spec.to_string(), source: e }
+    }
+
+    // ffmpeg's validation error doesn't say which filter was the problem, so the whole graph goes with it
+    pub fn validate(e: ffmpeg::Error, graph_dump: String) -> Self {
+        FilterError::Validate { source: e, graph_dump }
+    }
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::FilterNotFound(name) => write!(f, "filter {} isn't available in this ffmpeg build", name),
+            FilterError::AddFilter { name, source } => write!(f, "couldn't add the {} filter: {}", name, source),
+            FilterError::Link { pad, source } => write!(f, "couldn't link the {} pad: {}", pad, source),
+            FilterError::Parse { spec, source } => write!(f, "couldn't parse filter spec {:?}: {}", spec, source),
+            FilterError::Validate { source, graph_dump } => write!(f, "validation failed: {}\nGraph dump:\n{}", source, graph_dump),
+        }
+    }
+}
+
+impl std::error::Error for FilterError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FilterError::FilterNotFound(_) => None,
+            FilterError::AddFilter { source, .. }
+            | FilterError::Link { source, .. }
+            | FilterError::Parse { source, .. }
+            | FilterError::Validate { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<FilterError> for EncodeError {
+    fn from(e: FilterError) -> Self {
+        EncodeError::Filter(e)
+    }
+}
+
+// A VideoPlane that doesn't match the dimensions it claims
+#[derive(Debug)]
+pub enum ValidationError {
+    UnsupportedPixelFormat(Pixel),
+    PixelFormatMismatch { plane: Pixel, expected: Pixel },
+    PitchTooSmall { pitch: usize, row_bytes: usize },
+    DataTooShort { len: usize, expected: usize },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::UnsupportedPixelFormat(format) => write!(f, "can't check planes of pixel format {:?}", format),
+            ValidationError::PixelFormatMismatch { plane, expected } => write!(f, "plane is {:?}, but the encoder was configured for {:?}", plane, expected),
+            ValidationError::PitchTooSmall { pitch, row_bytes } => write!(f, "pitch {} is shorter than a row ({} bytes)", pitch, row_bytes),
+            ValidationError::DataTooShort { len, expected } => write!(f, "plane has {} bytes, expected at least {}", len, expected),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl From<ValidationError> for EncodeError {
+    fn from(e: ValidationError) -> Self {
+        EncodeError::InvalidFrame(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum InvalidPlane {
+    AlignmentNotPowerOfTwo(usize),
+    DataTooShort { len: usize, expected: usize },
+}
+
+impl fmt::Display for InvalidPlane {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidPlane::AlignmentNotPowerOfTwo(align) => write!(f, "row alignment must be a power of two, got {}", align),
+            InvalidPlane::DataTooShort { len, expected } => write!(f, "plane has {} bytes, expected at least {}", len, expected),
+        }
+    }
+}
+
+impl std::error::Error for InvalidPlane {}
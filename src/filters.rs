@@ -1,21 +1,23 @@
 extern crate ffmpeg_next as ffmpeg;
 
-use ffmpeg::filter;
+use std::ffi::CStr;
 
-use crate::encoder::{AudioArgs, VideoArgs};
+use ffmpeg::{filter, format::Pixel};
 
+use crate::{encoder::{AudioArgs, OPUS_NATIVE_SAMPLE_RATE, VideoArgs}, error::{EncodeError, FilterError}};
+
+fn find_filter(name: &'static str) -> Result<filter::Filter, FilterError> {
+    filter::find(name).ok_or(FilterError::FilterNotFound(name))
+}
+
+// `extra_filters` are ones added while encoding, see CollectedAVFfmpegEncoder::insert_filter_before_sink
 pub fn make_video_filter(
     video_encoder: &ffmpeg::encoder::video::Video,
-    video_args: &VideoArgs
-) -> Result<filter::Graph, ffmpeg::Error> {
-
-    let pixel_format_string = match video_args.pixel_format {
-        ffmpeg::format::Pixel::BGRA => "bgra",
-        ffmpeg::format::Pixel::RGB555 => if cfg!(target_endian = "big") { "rgb555be" } else { "rgb555le" },
-        ffmpeg::format::Pixel::RGB32 => "argb",
-        ffmpeg::format::Pixel::RGB565 => if cfg!(target_endian = "big") { "rgb565be" } else { "rgb565le" },
-        _ => {panic!("need to build pixel format strings in a more general way.");}
-    };
+    video_args: &VideoArgs,
+    extra_filters: &[String],
+) -> Result<filter::Graph, EncodeError> {
+
+    let pixel_format_string = pixel_format_name(video_args.pixel_format)?;
 
     let pixel_aspect = 1; // assume square pixels for now...
 
@@ -32,48 +34,126 @@ pub fn make_video_filter(
     );
     eprintln!("🎥 filter args: {}", args);
 
-    video_filter.add(&filter::find("buffer").unwrap(), "in", &args)?;
+    video_filter.add(&find_filter("buffer")?, "in", &args).map_err(|e| FilterError::add_filter("buffer", e))?;
     //scale?
-    video_filter.add(&filter::find("buffersink").unwrap(), "out", "")?;
+    video_filter.add(&find_filter("buffersink")?, "out", "").map_err(|e| FilterError::add_filter("buffersink", e))?;
 
     {
         let mut out = video_filter.get("out").unwrap();
         out.set_pixel_format(video_encoder.format());
     }
 
-    video_filter.output("in", 0)?
-        .input("out", 0)?
-        .parse("null")?; // passthrough filter for video
+    let filter_spec = match &video_args.watermark {
+        Some(watermark) => {
+            // the watermark image is read by a movie source feeding overlay's second input
+            find_filter("movie")?;
+            // negative offsets are measured from the right/bottom edge
+            let x = if watermark.x_offset < 0 { format!("main_w-overlay_w{}", watermark.x_offset) } else { watermark.x_offset.to_string() };
+            let y = if watermark.y_offset < 0 { format!("main_h-overlay_h{}", watermark.y_offset) } else { watermark.y_offset.to_string() };
+            format!(
+                "movie={},format=rgba,colorchannelmixer=aa={}[watermark];[in][watermark]overlay=x={}:y={}",
+                escape_filter_value(&watermark.image_path.to_string_lossy()),
+                watermark.alpha,
+                x,
+                y,
+            )
+        },
+        None => "null".to_string(), // passthrough filter for video
+    };
+    // overlay's output is left unlabeled above so this can carry on from it, and whatever ends up last feeds the sink
+    let filter_spec = if extra_filters.is_empty() {
+        filter_spec
+    } else {
+        format!("{},{}", filter_spec, extra_filters.join(","))
+    };
 
-    video_filter.validate()?;
+    video_filter.output("in", 0).map_err(|e| FilterError::link("in", e))?
+        .input("out", 0).map_err(|e| FilterError::link("out", e))?
+        .parse(&filter_spec).map_err(|e| FilterError::parse(&filter_spec, e))?;
+
+    validate_graph(&mut video_filter)?;
     // human-readable filter graph
     eprintln!("{}", video_filter.dump());
 
     Ok(video_filter)
 }
 
+const DEFAULT_LOUDNORM_PARAMS: &str = "I=-23:TP=-2:LRA=7";
+
 pub fn make_audio_filter(
     audio_encoder: &ffmpeg::codec::encoder::Audio,
-    audio_args: &AudioArgs
-) -> Result<filter::Graph, ffmpeg::Error> {
+    audio_args: &AudioArgs,
+    extra_filters: &[String],
+) -> Result<filter::Graph, EncodeError> {
+    build_audio_filter(audio_encoder, audio_args, extra_filters, None)
+}
+
+// The same graph, but fed by an aevalsrc generating `duration_secs` of silence instead of the "in" abuffer,
+// so nothing has to be pushed into it
+pub fn make_silence_filter(
+    audio_encoder: &ffmpeg::codec::encoder::Audio,
+    audio_args: &AudioArgs,
+    duration_secs: f64,
+) -> Result<filter::Graph, EncodeError> {
+    build_audio_filter(audio_encoder, audio_args, &[], Some(duration_secs))
+}
+
+fn build_audio_filter(
+    audio_encoder: &ffmpeg::codec::encoder::Audio,
+    audio_args: &AudioArgs,
+    extra_filters: &[String],
+    silence_secs: Option<f64>,
+) -> Result<filter::Graph, EncodeError> {
     let mut afilter = filter::Graph::new();
-    let args = format!("time_base=1/44100:sample_rate={}:sample_fmt=s16:channel_layout=stereo", audio_args.sample_rate);
-    eprintln!("🔊 filter args: {}", args);
-    afilter.add(&filter::find("abuffer").unwrap(), "in", &args)?;
+    if silence_secs.is_none() {
+        let args = format!("time_base=1/44100:sample_rate={}:sample_fmt=s16:channel_layout=stereo", audio_args.sample_rate);
+        eprintln!("🔊 filter args: {}", args);
+        afilter.add(&find_filter("abuffer")?, "in", &args).map_err(|e| FilterError::add_filter("abuffer", e))?;
+    }
     //aresample?
-    afilter.add(&filter::find("abuffersink").unwrap(), "out", "")?;
+    afilter.add(&find_filter("abuffersink")?, "out", "").map_err(|e| FilterError::add_filter("abuffersink", e))?;
 
     {
+        // the sink converts from the s16 the planes come in to whatever the encoder takes (planar float for vorbis/opus)
         let mut out = afilter.get("out").unwrap();
         out.set_sample_format(audio_encoder.format());
         out.set_channel_layout(audio_encoder.channel_layout());
         out.set_sample_rate(audio_encoder.rate());
     }
 
-    afilter.output("in", 0)?
-        .input("out", 0)?
-        .parse("anull")?;
-    afilter.validate()?;
+    let mut filter_chain = Vec::new();
+    if let Some(duration_secs) = silence_secs {
+        find_filter("aevalsrc")?;
+        filter_chain.push(format!("aevalsrc=0:d={}:s={}:c=stereo", duration_secs, audio_args.sample_rate));
+    }
+    if let Some(threshold_db) = audio_args.silence_threshold_db {
+        // ahead of loudnorm, so it's the game's levels being measured and not the normalized ones
+        filter_chain.push(format!("silencedetect=noise={}dB:duration=0.5", threshold_db));
+    }
+    if audio_args.volume != 1.0 {
+        filter_chain.push(format!("volume={}", audio_args.volume));
+    }
+    if audio_args.normalize {
+        // loudnorm upsamples internally, the sink converts back to the encoder's rate
+        filter_chain.push(format!("loudnorm={}", audio_args.loudnorm_params.as_deref().unwrap_or(DEFAULT_LOUDNORM_PARAMS)));
+    }
+    if audio_encoder.codec().map(|c| c.id()) == Some(ffmpeg::codec::Id::OPUS) && audio_args.sample_rate != OPUS_NATIVE_SAMPLE_RATE {
+        filter_chain.push(format!("aresample={}", OPUS_NATIVE_SAMPLE_RATE));
+    }
+    filter_chain.extend(extra_filters.iter().cloned());
+    let filter_spec = if filter_chain.is_empty() {
+        "anull".to_string() // passthrough filter for audio
+    } else {
+        filter_chain.join(",")
+    };
+
+    let parser = match silence_secs {
+        Some(_) => afilter.input("out", 0).map_err(|e| FilterError::link("out", e))?,
+        None => afilter.output("in", 0).map_err(|e| FilterError::link("in", e))?
+            .input("out", 0).map_err(|e| FilterError::link("out", e))?,
+    };
+    parser.parse(&filter_spec).map_err(|e| FilterError::parse(&filter_spec, e))?;
+    validate_graph(&mut afilter)?;
     // human-readable filter graph
     eprintln!("{}", afilter.dump());
 
@@ -92,4 +172,35 @@ pub fn make_audio_filter(
     }
 
     Ok(afilter)
-}
\ No newline at end of file
+}
+
+// ffmpeg's name for the format, which for the native endian aliases (RGB565, RGB32, ...) is the concrete le/be one
+fn pixel_format_name(pixel_format: Pixel) -> Result<String, EncodeError> {
+    unsafe {
+        let name = ffmpeg::sys::av_get_pix_fmt_name(pixel_format.into());
+        if name.is_null() {
+            return Err(EncodeError::InvalidPixelFormat(pixel_format));
+        }
+        Ok(CStr::from_ptr(name).to_string_lossy().into_owned())
+    }
+}
+
+fn validate_graph(graph: &mut filter::Graph) -> Result<(), FilterError> {
+    graph.validate().map_err(|e| FilterError::validate(e, graph.dump()))
+}
+
+// Escapes a filter option value for a filtergraph description, which gets unescaped twice:
+// once when the graph is split into filters and once when each filter's options are parsed
+fn escape_filter_value(value: &str) -> String {
+    fn escape(value: &str, special: &[char]) -> String {
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            if special.contains(&c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+    escape(&escape(value, &['\\', '\'', ':']), &['\\', '\'', '[', ']', ',', ';'])
+}
@@ -1,5 +1,10 @@
+//! Encodes audio and video frames collected from a libretro frontend using ffmpeg.
+//!
+//! Requires FFmpeg 5.0 or newer; initialization fails with `EncodeError::VersionMismatch` on older libraries.
+
 extern crate crossbeam_channel;
 
 pub mod sink;
 pub mod encoder;
+pub mod error;
 mod filters;
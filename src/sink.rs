@@ -1,6 +1,13 @@
-use crossbeam_channel::{Receiver, SendError, Sender};
+extern crate ffmpeg_next as ffmpeg;
 
-use crate::encoder::OutputArgs;
+use std::{ops::Deref, sync::{Arc, Mutex}};
+
+use crossbeam_channel::{Receiver, SendError, Sender, TryRecvError, TrySendError};
+pub use ffmpeg::media::Type as MediaType;
+
+use ffmpeg::format::Pixel;
+
+use crate::{encoder::{AudioArgs, OutputArgs, VideoArgs}, error::{InvalidPlane, ValidationError}};
 
 pub struct Sink<T> {
     pub input: Sender<T>,
@@ -17,65 +24,565 @@ impl Default for Sink<Frame<FrameData>> {
     }
 }
 
+impl Sink<Frame<FrameData>> {
+    // a channel holding at most `capacity` frames, see DropPolicy for what happens when it's full
+    pub fn bounded(capacity: usize) -> Self {
+        let channel = crossbeam_channel::bounded(capacity);
+        Sink {
+            input: channel.0,
+            output: channel.1
+        }
+    }
+}
+
+// Where the collector sends frames. Implemented for channel senders and BroadcastSink.
+pub trait FrameSink: Send {
+    fn send(&self, frame: Frame<FrameData>) -> Result<(), SendError<Frame<FrameData>>>;
+    fn try_send(&self, frame: Frame<FrameData>) -> Result<(), TrySendError<Frame<FrameData>>>;
+}
+
+impl FrameSink for Sender<Frame<FrameData>> {
+    fn send(&self, frame: Frame<FrameData>) -> Result<(), SendError<Frame<FrameData>>> {
+        Sender::send(self, frame)
+    }
+
+    fn try_send(&self, frame: Frame<FrameData>) -> Result<(), TrySendError<Frame<FrameData>>> {
+        Sender::try_send(self, frame)
+    }
+}
+
+// Hands a copy of everything pushed to each receiver, e.g. to feed encoders at several bit rates from one source.
+// Clones share the list of receivers, so one can be kept around to add receivers after the other is given away.
+#[derive(Debug)]
+pub struct BroadcastSink<T: Clone> {
+    senders: Arc<Mutex<Vec<Sender<T>>>>,
+}
+
+impl<T: Clone> Clone for BroadcastSink<T> {
+    fn clone(&self) -> Self {
+        BroadcastSink { senders: self.senders.clone() }
+    }
+}
+
+impl<T: Clone> Default for BroadcastSink<T> {
+    fn default() -> Self {
+        BroadcastSink { senders: Default::default() }
+    }
+}
+
+impl<T: Clone> BroadcastSink<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // receives everything pushed from now on
+    pub fn add_receiver(&self) -> Receiver<T> {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        self.senders.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.senders.lock().unwrap().len()
+    }
+
+    // Returns the copies that couldn't be delivered. Receivers that have gone away are forgotten.
+    pub fn push(&self, frame: T) -> Vec<TrySendError<T>> {
+        let mut errors = Vec::new();
+        self.senders.lock().unwrap().retain(|sender| match sender.try_send(frame.clone()) {
+            Ok(()) => true,
+            Err(e) => {
+                let connected = !e.is_disconnected();
+                errors.push(e);
+                connected
+            },
+        });
+        errors
+    }
+}
+
+impl FrameSink for BroadcastSink<Frame<FrameData>> {
+    // only an error when nobody got the frame
+    fn send(&self, frame: Frame<FrameData>) -> Result<(), SendError<Frame<FrameData>>> {
+        let receivers = self.receiver_count();
+        let errors = self.push(frame.clone());
+        if receivers > 0 && errors.len() == receivers {
+            return Err(SendError(frame));
+        }
+        Ok(())
+    }
+
+    // the receivers are unbounded, so this never reports Full
+    fn try_send(&self, frame: Frame<FrameData>) -> Result<(), TrySendError<Frame<FrameData>>> {
+        self.send(frame).map_err(|SendError(frame)| TrySendError::Disconnected(frame))
+    }
+}
+
+#[derive(Clone)]
 pub struct Frame<T> {
     pub data: T,
     pub frame_number: u64,
 }
 
-#[derive(Debug)]
+// Where the encoder thread pulls frames from. Implemented for channel receivers; tests can supply their own.
+pub trait FrameSource: Send {
+    fn try_recv(&self) -> Result<Frame<FrameData>, FrameSourceError>;
+    fn is_empty(&self) -> bool;
+    fn len(&self) -> usize;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameSourceError {
+    Empty,
+    Disconnected,
+}
+
+impl FrameSource for Receiver<Frame<FrameData>> {
+    fn try_recv(&self) -> Result<Frame<FrameData>, FrameSourceError> {
+        Receiver::try_recv(self).map_err(|e| match e {
+            TryRecvError::Empty => FrameSourceError::Empty,
+            TryRecvError::Disconnected => FrameSourceError::Disconnected,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        Receiver::is_empty(self)
+    }
+
+    fn len(&self) -> usize {
+        Receiver::len(self)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum FrameData {
     Video(VideoPlane),
     Audio(AudioPlane),
+    // Same as Video/Audio, but with the pts given explicitly instead of derived from the frame number, for variable
+    // frame rate sources. Video pts is in units of 1/fps, audio pts in units of 1/44100.
+    VideoWithPts { plane: VideoPlane, pts: i64 },
+    AudioWithPts { plane: AudioPlane, pts: i64 },
+    // Metadata tied to this frame number. For H.264/H.265 video it's embedded in the next video frame as an
+    // unregistered user data SEI; otherwise it's appended to a .meta.jsonl file next to the output.
+    UserData { key: String, value: Vec<u8>, stream_type: MediaType },
+    // One subtitle cue, shown from start_ms to end_ms into the recording. Needs VideoArgs::subtitles to be set.
+    Subtitle { text: String, start_ms: u64, end_ms: u64 },
+    // Heartbeat for when the core isn't producing audio or video (pause screens, menus). The encoder pushes out
+    // whatever it has buffered and sends an EncodeEvent::Heartbeat.
+    Noop,
     Configure(OutputArgs),
     End,
 }
 
+#[derive(Debug)]
+pub enum CollectorError {
+    MismatchedChannelLengths { left: usize, right: usize },
+    Send(SendError<Frame<FrameData>>),
+}
+
+impl From<SendError<Frame<FrameData>>> for CollectorError {
+    fn from(e: SendError<Frame<FrameData>>) -> Self {
+        CollectorError::Send(e)
+    }
+}
+
+// What to do with a video or audio frame when a bounded channel is full. Configure and End always wait for room.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DropPolicy {
+    // throw away the frame being sent
+    DropNewest,
+    // throw away the video or audio frame that has been waiting longest, to make room. Configure, End and cues
+    // can't be put back in front of what's queued behind them, so while one might still be queued this drops the
+    // frame being sent instead. Only the collector's own channel can be made room in; with a custom frame sink
+    // this is the same as DropNewest.
+    DropOldest,
+    // wait for the encoder to catch up
+    Block,
+}
+
+#[derive(Debug, Clone)]
+pub struct CollectorConfig {
+    // None for an unbounded channel, which never drops anything
+    pub channel_capacity: Option<usize>,
+    pub drop_policy: DropPolicy,
+}
+
+impl Default for CollectorConfig {
+    fn default() -> Self {
+        CollectorConfig {
+            channel_capacity: None,
+            drop_policy: DropPolicy::Block,
+        }
+    }
+}
+
 pub struct RetroAVCollector {
     pub sink: Sink<Frame<FrameData>>,
+    // sent to instead of `sink` when set, which then stays empty
+    frame_sink: Option<Box<dyn FrameSink>>,
+    drop_policy: DropPolicy,
+    frames_dropped: u64,
+    // video/audio frames sent since the last Configure/End/cue. The channel holds the most recently sent frames
+    // (as long as nothing but the collector sends to it),
+    // so when it holds no more than this many, everything in it is safe for DropOldest to evict.
+    media_frames_since_control: u64,
 
     audio_buf: Vec<(i16, i16)>, // accumulate audio for slicing into planes
+
+    // what has actually made it into the channel, for figuring out where frames get dropped
+    video_frames_submitted: u64,
+    audio_samples_submitted: u64,
+
+    // added to incoming frame numbers, so a resumed session continues the previous one's timestamps
+    pts_offset: u64,
+    // one past the highest frame number passed in since the last reset
+    frame_counter: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct VideoPlane {
-    pub data: Vec<u8>,
+    pub data: VideoData,
     pub width: usize,
     pub height: usize,
     pub pitch: usize,
+    // the format the data is known to be in, checked against the encoder's when set
+    pub pixel_format: Option<Pixel>,
 }
 
-#[derive(Debug)]
+// Pixel data that's either ours or shared with whoever produced it (e.g. a GPU readback buffer), to skip a copy
+#[derive(Debug, Clone)]
+pub enum VideoData {
+    Owned(Vec<u8>),
+    Shared(Arc<[u8]>),
+}
+
+impl Deref for VideoData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            VideoData::Owned(data) => data,
+            VideoData::Shared(data) => data,
+        }
+    }
+}
+
+impl From<Vec<u8>> for VideoData {
+    fn from(data: Vec<u8>) -> Self {
+        VideoData::Owned(data)
+    }
+}
+
+impl From<Arc<[u8]>> for VideoData {
+    fn from(data: Arc<[u8]>) -> Self {
+        VideoData::Shared(data)
+    }
+}
+
+impl VideoPlane {
+    // Native endian 16 bit pixels, which is what the filter graph expects Pixel::RGB565 to be
+    pub fn from_rgb565(data: &[u16], width: u32, height: u32) -> VideoPlane {
+        let mut bytes = Vec::with_capacity(data.len() * 2);
+        for pixel in data {
+            bytes.extend_from_slice(&pixel.to_ne_bytes());
+        }
+        VideoPlane {
+            data: bytes.into(),
+            width: width as usize,
+            height: height as usize,
+            pitch: width as usize * 2,
+            pixel_format: Some(Pixel::RGB565),
+        }
+    }
+
+    // For sources whose rows are padded out to a multiple of `align` bytes (GPU textures, hardware decoders)
+    pub fn stride_aligned(data: Vec<u8>, width: usize, height: usize, bytes_per_pixel: usize, align: usize) -> Result<VideoPlane, InvalidPlane> {
+        if !align.is_power_of_two() {
+            return Err(InvalidPlane::AlignmentNotPowerOfTwo(align));
+        }
+        let pitch = (width * bytes_per_pixel + align - 1) & !(align - 1);
+        let expected = pitch * height;
+        if data.len() < expected {
+            return Err(InvalidPlane::DataTooShort { len: data.len(), expected });
+        }
+        Ok(VideoPlane {
+            data: data.into(),
+            width,
+            height,
+            pitch,
+            pixel_format: None,
+        })
+    }
+
+    // Checks that every row the encoder copies out of `data` is actually there
+    pub fn validate(&self, pixel_format: Pixel) -> Result<(), ValidationError> {
+        if let Some(plane_format) = self.pixel_format {
+            if plane_format != pixel_format {
+                return Err(ValidationError::PixelFormatMismatch { plane: plane_format, expected: pixel_format });
+            }
+        }
+        let row_bytes = unsafe {
+            let descriptor = ffmpeg::sys::av_pix_fmt_desc_get(pixel_format.into());
+            if descriptor.is_null() {
+                return Err(ValidationError::UnsupportedPixelFormat(pixel_format));
+            }
+            if (*descriptor).flags & ffmpeg::sys::AV_PIX_FMT_FLAG_PLANAR as u64 != 0 {
+                // `pitch` is the first plane's, whose pixels are `step` bytes each
+                self.width * (*descriptor).comp[0].step as usize
+            } else {
+                (self.width * ffmpeg::sys::av_get_bits_per_pixel(descriptor) as usize + 7) / 8
+            }
+        };
+        if self.pitch < row_bytes {
+            return Err(ValidationError::PitchTooSmall { pitch: self.pitch, row_bytes });
+        }
+        let expected: usize = self.plane_layout(pixel_format).iter().map(|(pitch, rows)| pitch * rows).sum();
+        if self.data.len() < expected {
+            return Err(ValidationError::DataTooShort { len: self.data.len(), expected });
+        }
+        Ok(())
+    }
+
+    // (pitch, rows) of each plane, stored one after the other in `data`. Packed formats have just the one; for
+    // planar formats (e.g. YUV420P) the chroma planes follow the first plane at its pitch and height divided down
+    // by the format's chroma subsampling.
+    pub(crate) fn plane_layout(&self, pixel_format: Pixel) -> Vec<(usize, usize)> {
+        let (plane_count, chroma_w, chroma_h) = unsafe {
+            let descriptor = ffmpeg::sys::av_pix_fmt_desc_get(pixel_format.into());
+            if descriptor.is_null() || (*descriptor).flags & ffmpeg::sys::AV_PIX_FMT_FLAG_PLANAR as u64 == 0 {
+                return vec![(self.pitch, self.height)];
+            }
+            let plane_count = ffmpeg::sys::av_pix_fmt_count_planes(pixel_format.into()).max(1) as usize;
+            (plane_count, (*descriptor).log2_chroma_w as u32, (*descriptor).log2_chroma_h as u32)
+        };
+        let subsampled = |size: usize, log2: u32| (size + (1 << log2) - 1) >> log2;
+        (0..plane_count)
+            .map(|plane| match plane {
+                // a fourth plane is alpha, which is full size
+                1 | 2 => (subsampled(self.pitch, chroma_w), subsampled(self.height, chroma_h)),
+                _ => (self.pitch, self.height),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct AudioPlane {
-    pub data: Vec<(i16, i16)>
+    pub data: Vec<(i16, i16)>,
+    // where the first sample falls, counted in samples at AudioArgs::sample_rate; the frame number is used when None
+    pub pts_samples: Option<u64>,
 }
 
 impl RetroAVCollector {
     pub fn new() -> Self{
+        Self::with_config(CollectorConfig::default())
+    }
+
+    pub fn with_config(config: CollectorConfig) -> Self {
         RetroAVCollector {
-            sink: Default::default(),
+            sink: match config.channel_capacity {
+                Some(capacity) => Sink::bounded(capacity),
+                None => Default::default(),
+            },
+            frame_sink: None,
+            drop_policy: config.drop_policy,
+            frames_dropped: 0,
+            media_frames_since_control: 0,
             audio_buf: Default::default(),
+            video_frames_submitted: 0,
+            audio_samples_submitted: 0,
+            pts_offset: 0,
+            frame_counter: 0,
+        }
+    }
+
+    // Sends frames to `frame_sink` (e.g. a BroadcastSink) instead of this collector's own channel.
+    pub fn with_frame_sink(config: CollectorConfig, frame_sink: impl FrameSink + 'static) -> Self {
+        RetroAVCollector {
+            frame_sink: Some(Box::new(frame_sink)),
+            ..Self::with_config(config)
+        }
+    }
+
+    fn output(&self) -> &dyn FrameSink {
+        match &self.frame_sink {
+            Some(frame_sink) => frame_sink.as_ref(),
+            None => &self.sink.input,
+        }
+    }
+
+    // video and audio frames thrown away by the drop policy
+    pub fn frames_dropped(&self) -> u64 {
+        self.frames_dropped
+    }
+
+    pub fn video_frames_submitted(&self) -> u64 {
+        self.video_frames_submitted
+    }
+
+    // counted in stereo sample pairs
+    pub fn audio_samples_submitted(&self) -> u64 {
+        self.audio_samples_submitted
+    }
+
+    // stereo sample pairs collected but not sent yet
+    pub fn audio_buf_len(&self) -> usize {
+        self.audio_buf.len()
+    }
+
+    pub fn audio_buf_capacity(&self) -> usize {
+        self.audio_buf.capacity()
+    }
+
+    // Frames sitting in the channel waiting for the encoder.
+    // None when sending to a custom frame sink, whose queues the collector can't see into.
+    pub fn channel_depth(&self) -> Option<usize> {
+        match self.frame_sink {
+            Some(_) => None,
+            None => Some(self.sink.input.len()),
+        }
+    }
+
+    // None for an unbounded channel, or when sending to a custom frame sink
+    pub fn channel_capacity(&self) -> Option<usize> {
+        match self.frame_sink {
+            Some(_) => None,
+            None => self.sink.input.capacity(),
         }
     }
 
     pub fn configure(&mut self, output_args: &OutputArgs, frame_number: u64) -> Result<(), SendError<Frame<FrameData>>> {
-        self.sink.input.send(Frame {
+        self.send_control_frame(Frame {
             data: FrameData::Configure(output_args.clone()),
             frame_number,
         })
     }
 
+    // frame number the previous session ended at, when resuming it
+    pub fn set_pts_offset(&mut self, offset: u64) {
+        self.pts_offset = offset;
+    }
+
+    pub fn reset_frame_counter(&mut self) {
+        self.frame_counter = 0;
+    }
+
+    pub fn increment_pts_offset_by_frame_count(&mut self, frames: u64) {
+        self.pts_offset += frames;
+    }
+
+    // Call after end() and before configuring the next encoder, so its frame numbers can start at 0 again
+    // while its timestamps carry on from where this session's stopped
+    pub fn reset(&mut self) {
+        self.increment_pts_offset_by_frame_count(self.frame_counter);
+        self.reset_frame_counter();
+    }
+
+    pub fn configure_video_only(&mut self, args: VideoArgs, frame_number: u64) -> Result<(), SendError<Frame<FrameData>>> {
+        self.configure(&OutputArgs::Video(args), frame_number)
+    }
+
+    pub fn configure_audio_only(&mut self, args: AudioArgs, frame_number: u64) -> Result<(), SendError<Frame<FrameData>>> {
+        self.configure(&OutputArgs::Audio(args), frame_number)
+    }
+
     pub fn on_video_refresh(&mut self, data: &[u8], width: u32, height: u32, pitch: u32, frame_number: u64) -> Result<(), SendError<Frame<FrameData>>> {
-        let plane = VideoPlane {
-            data: data.to_vec(),
+        self.send_video_plane(VideoPlane {
+            data: data.to_vec().into(),
             width: width as usize,
             height: height as usize,
-            pitch: pitch as usize
-        };
+            pitch: pitch as usize,
+            pixel_format: None,
+        }, frame_number)
+    }
+
+    // Same as on_video_refresh, but the data is handed over as is instead of copied
+    pub fn on_video_refresh_shared(&mut self, data: Arc<[u8]>, width: u32, height: u32, pitch: u32, frame_number: u64) -> Result<(), SendError<Frame<FrameData>>> {
+        self.send_video_plane(VideoPlane {
+            data: data.into(),
+            width: width as usize,
+            height: height as usize,
+            pitch: pitch as usize,
+            pixel_format: None,
+        }, frame_number)
+    }
+
+    fn send_video_plane(&mut self, plane: VideoPlane, frame_number: u64) -> Result<(), SendError<Frame<FrameData>>> {
+        self.frame_counter = self.frame_counter.max(frame_number + 1);
         let frame = Frame {
             data: FrameData::Video(plane),
-            frame_number
+            frame_number: frame_number + self.pts_offset,
         };
-        self.sink.input.send(frame)
+        if self.send_media_frame(frame)? {
+            self.video_frames_submitted += 1;
+        }
+        Ok(())
+    }
+
+    // Sends following the drop policy; Ok(false) if the frame itself was dropped
+    fn send_media_frame(&mut self, mut frame: Frame<FrameData>) -> Result<bool, SendError<Frame<FrameData>>> {
+        match self.drop_policy {
+            DropPolicy::Block => {
+                self.output().send(frame)?;
+                Ok(true)
+            },
+            DropPolicy::DropNewest => match self.output().try_send(frame) {
+                Ok(()) => Ok(true),
+                Err(TrySendError::Disconnected(frame)) => Err(SendError(frame)),
+                Err(TrySendError::Full(rejected)) => {
+                    eprintln!("Frame channel is full, dropping frame {}", rejected.frame_number);
+                    self.frames_dropped += 1;
+                    Ok(false)
+                },
+            },
+            DropPolicy::DropOldest => {
+                loop {
+                    match self.output().try_send(frame) {
+                        Ok(()) => {
+                            self.media_frames_since_control += 1;
+                            return Ok(true);
+                        },
+                        Err(TrySendError::Disconnected(frame)) => return Err(SendError(frame)),
+                        Err(TrySendError::Full(rejected)) => {
+                            // the encoder only ever takes frames off the front, so if everything queued was media
+                            // when checked, whatever try_recv gets is media too
+                            let only_media_queued = self.frame_sink.is_none()
+                                && self.sink.output.len() as u64 <= self.media_frames_since_control;
+                            // nothing to take means a zero capacity channel, or the encoder emptying it meanwhile
+                            let oldest = if only_media_queued { self.sink.output.try_recv().ok() } else { None };
+                            match oldest {
+                                Some(oldest) => {
+                                    eprintln!("Frame channel is full, dropping frame {}", oldest.frame_number);
+                                    self.uncount_dropped(&oldest);
+                                    frame = rejected;
+                                },
+                                None => {
+                                    eprintln!("Frame channel is full, dropping frame {}", rejected.frame_number);
+                                    self.frames_dropped += 1;
+                                    return Ok(false);
+                                },
+                            }
+                        },
+                    }
+                }
+            },
+        }
+    }
+
+    // for a frame that was counted as submitted when it went into the channel, and then evicted by DropOldest
+    fn uncount_dropped(&mut self, frame: &Frame<FrameData>) {
+        self.frames_dropped += 1;
+        match &frame.data {
+            FrameData::Video(_) => self.video_frames_submitted -= 1,
+            FrameData::Audio(plane) => self.audio_samples_submitted -= plane.data.len() as u64,
+            _ => {},
+        }
+    }
+
+    // Configure, End and cues are never dropped, they wait for room
+    fn send_control_frame(&mut self, frame: Frame<FrameData>) -> Result<(), SendError<Frame<FrameData>>> {
+        self.media_frames_since_control = 0;
+        self.output().send(frame)
     }
 
     pub fn on_audio_sample(&mut self, left: i16, right: i16, frame_number: u64) {
@@ -90,26 +597,158 @@ impl RetroAVCollector {
         stereo_pcm.len()
     }
 
+    // For sources that count their own samples (capture cards, mixers), where frame numbers would only approximate
+    // the timing. `sample_offset` is the position of the batch's first sample at AudioArgs::sample_rate, and isn't
+    // affected by pts_offset.
+    pub fn on_audio_sample_batch_at_sample_offset(&mut self, stereo_pcm: &[i16], sample_offset: u64) -> Result<usize, CollectorError> {
+        // anything still buffered came right before this batch
+        let first_sample = sample_offset.saturating_sub(self.audio_buf.len() as u64);
+        let left_iter = stereo_pcm.iter().step_by(2).cloned();
+        let right_iter = stereo_pcm.iter().skip(1).step_by(2).cloned();
+        self.audio_buf.extend(Iterator::zip(left_iter, right_iter));
+        self.send_audio_plane(self.frame_counter.saturating_sub(1), Some(first_sample))?;
+        Ok(stereo_pcm.len())
+    }
+
+    // For sources that produce interleaved stereo floats, where -1.0..=1.0 is full scale.
+    // Samples outside that range are clipped to it rather than wrapping around.
+    pub fn on_audio_interleaved_f32(&mut self, data: &[f32], frame_number: u64) -> Result<usize, CollectorError> {
+        let to_i16 = |sample: f32| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        self.audio_buf.extend(data.chunks_exact(2).map(|pair| (to_i16(pair[0]), to_i16(pair[1]))));
+        self.send_audio_plane_if_ready(frame_number)?;
+        Ok(data.len())
+    }
+
+    pub fn on_audio_planar_i16(&mut self, left: &[i16], right: &[i16], frame_number: u64) -> Result<usize, CollectorError> {
+        // for sources that hand us each channel separately instead of interleaved
+        if left.len() != right.len() {
+            return Err(CollectorError::MismatchedChannelLengths { left: left.len(), right: right.len() });
+        }
+        self.audio_buf.extend(Iterator::zip(left.iter().cloned(), right.iter().cloned()));
+        self.send_audio_plane_if_ready(frame_number)?;
+        Ok(left.len())
+    }
+
     fn send_audio_plane_if_ready(&mut self, frame_number: u64) -> Result<(), SendError<Frame<FrameData>>> {
+        self.frame_counter = self.frame_counter.max(frame_number + 1);
+        self.send_audio_plane(frame_number, None)
+    }
+
+    fn send_audio_plane(&mut self, frame_number: u64, pts_samples: Option<u64>) -> Result<(), SendError<Frame<FrameData>>> {
         // current code crams the entire buffer into a plane if it's ready
         // should i use sample rate here?
         // current code ends up collecting ~735 samples on picodrive
         let data = self.audio_buf.clone();
+        let sample_count = data.len() as u64;
         let plane = AudioPlane {
-            data
+            data,
+            pts_samples,
         };
         self.audio_buf.clear();
         let frame = Frame {
             data: FrameData::Audio(plane),
-            frame_number,
+            frame_number: frame_number + self.pts_offset,
         };
-        self.sink.input.send(frame)
+        if self.send_media_frame(frame)? {
+            self.audio_samples_submitted += sample_count;
+        }
+        Ok(())
+    }
+
+    pub fn on_subtitle(&mut self, text: &str, start_ms: u64, end_ms: u64, frame_number: u64) -> Result<(), SendError<Frame<FrameData>>> {
+        self.send_control_frame(Frame {
+            data: FrameData::Subtitle { text: text.to_string(), start_ms, end_ms },
+            frame_number,
+        })
     }
 
     pub fn end(&mut self, frame_number: u64) -> Result<(), SendError<Frame<FrameData>>>{
-        self.sink.input.send(Frame{
+        self.send_control_frame(Frame{
             data: FrameData::End,
             frame_number,
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn on_audio_sample_batch_submits_every_sample_pair(batches in prop::collection::vec(prop::collection::vec(any::<i16>(), 0..4096), 0..16)) {
+            let mut collector = RetroAVCollector::new();
+            let mut expected_pairs = 0;
+            for (frame_number, batch) in batches.iter().enumerate() {
+                prop_assert_eq!(collector.on_audio_sample_batch(batch, frame_number as u64), batch.len());
+                // an odd trailing sample has no partner and is dropped
+                expected_pairs += (batch.len() / 2) as u64;
+            }
+
+            prop_assert_eq!(collector.audio_samples_submitted(), expected_pairs);
+            prop_assert_eq!(collector.audio_buf_len(), 0);
+            let received: usize = collector.sink.output.try_iter()
+                .map(|frame| match frame.data {
+                    FrameData::Audio(plane) => plane.data.len(),
+                    _ => 0,
+                })
+                .sum();
+            prop_assert_eq!(received as u64, expected_pairs);
+        }
+    }
+
+    #[test]
+    fn reset_continues_frame_numbers_from_the_previous_session() {
+        let mut collector = RetroAVCollector::new();
+        let plane = [0u8; 16];
+        for frame_number in 0..3 {
+            collector.on_video_refresh(&plane, 2, 2, 8, frame_number).unwrap();
+        }
+        collector.end(3).unwrap();
+        collector.reset();
+        collector.on_video_refresh(&plane, 2, 2, 8, 0).unwrap();
+
+        let frame_numbers: Vec<u64> = collector.sink.output.try_iter()
+            .filter(|frame| matches!(frame.data, FrameData::Video(_)))
+            .map(|frame| frame.frame_number)
+            .collect();
+        assert_eq!(frame_numbers, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn drop_oldest_keeps_configure_and_end_in_order() {
+        let mut collector = RetroAVCollector::with_config(CollectorConfig {
+            channel_capacity: Some(2),
+            drop_policy: DropPolicy::DropOldest,
+        });
+        let plane = [0u8; 16];
+        let describe = |frame: Frame<FrameData>| match frame.data {
+            FrameData::Configure(_) => "configure".to_string(),
+            FrameData::Video(_) => format!("video {}", frame.frame_number),
+            FrameData::End => "end".to_string(),
+            _ => "other".to_string(),
+        };
+
+        // with Configure at the front, frames 1 and 2 are the ones to go
+        collector.configure_video_only(VideoArgs::new(Pixel::BGRA, 60, 2, 2), 0).unwrap();
+        for frame_number in 0..3 {
+            collector.on_video_refresh(&plane, 2, 2, 8, frame_number).unwrap();
+        }
+        let received: Vec<String> = collector.sink.output.try_iter().map(describe).collect();
+        assert_eq!(received, vec!["configure", "video 0"]);
+
+        // with only video queued, the oldest one makes room
+        for frame_number in 3..6 {
+            collector.on_video_refresh(&plane, 2, 2, 8, frame_number).unwrap();
+        }
+        let received: Vec<String> = collector.sink.output.try_iter().map(describe).collect();
+        assert_eq!(received, vec!["video 4", "video 5"]);
+        assert_eq!(collector.frames_dropped(), 3);
+        assert_eq!(collector.video_frames_submitted(), 3);
+
+        collector.end(6).unwrap();
+        let received: Vec<String> = collector.sink.output.try_iter().map(describe).collect();
+        assert_eq!(received, vec!["end"]);
+    }
+}
@@ -0,0 +1,35 @@
+#![cfg(feature = "integration-tests")]
+
+mod common;
+
+use common::{SAMPLE_RATE, SAMPLES_PER_FRAME};
+use libav_frame_encoder::{encoder::{start_thread, AudioArgs}, sink::RetroAVCollector};
+
+const SECONDS: u32 = 2;
+
+#[test]
+fn encodes_two_seconds_of_audio() {
+    let path = common::output_path("audio_only.m4a");
+    let mut collector = RetroAVCollector::new();
+    let encoder = start_thread(collector.sink.output.clone(), path.clone());
+
+    collector.configure_audio_only(AudioArgs::new(SAMPLE_RATE), 0).unwrap();
+    let frame_count = (SAMPLE_RATE * SECONDS / SAMPLES_PER_FRAME) as u64;
+    let samples = common::stereo_square_wave(SECONDS);
+    for (frame_number, stereo_pcm) in samples.chunks(SAMPLES_PER_FRAME as usize * 2).enumerate() {
+        collector.on_audio_sample_batch(stereo_pcm, frame_number as u64);
+    }
+    assert_eq!(collector.audio_samples_submitted(), (SAMPLE_RATE * SECONDS) as u64);
+    collector.end(frame_count).unwrap();
+    encoder.join().expect("encoder thread panicked").expect("encoding failed");
+
+    let probe = common::ffprobe(&path);
+    let stream = &probe["streams"][0];
+    assert_eq!(common::json_f64(&stream["sample_rate"]) as u32, SAMPLE_RATE);
+    let time_base = stream["time_base"].as_str().unwrap();
+    let (num, den) = time_base.split_once('/').unwrap();
+    let duration = stream["duration_ts"].as_f64().unwrap() * num.parse::<f64>().unwrap() / den.parse::<f64>().unwrap();
+    assert!((duration - SECONDS as f64).abs() < 0.1, "duration was {}s", duration);
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,42 @@
+// not every test uses every helper
+#![allow(dead_code)]
+
+use std::{path::{Path, PathBuf}, process::Command};
+
+pub const SAMPLE_RATE: u32 = 44100;
+// the encoder counts audio frames in 1/60s, same as a core running at 60fps
+pub const SAMPLES_PER_FRAME: u32 = SAMPLE_RATE / 60;
+
+// somewhere to write a test's output that won't collide with other tests running in parallel
+pub fn output_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("libav-frame-encoder-{}-{}", std::process::id(), name))
+}
+
+// ffprobe's -show_format -show_streams output for the file
+pub fn ffprobe(path: &Path) -> serde_json::Value {
+    let output = Command::new("ffprobe")
+        .args(&["-v", "error", "-show_format", "-show_streams", "-of", "json"])
+        .arg(path)
+        .output()
+        .expect("failed to run ffprobe, is it installed?");
+    assert!(output.status.success(), "ffprobe failed: {}", String::from_utf8_lossy(&output.stderr));
+    serde_json::from_slice(&output.stdout).expect("ffprobe didn't return json")
+}
+
+// ffprobe reports most numbers as strings
+pub fn json_f64(value: &serde_json::Value) -> f64 {
+    value.as_str()
+        .and_then(|s| s.parse().ok())
+        .or_else(|| value.as_f64())
+        .unwrap_or_else(|| panic!("{} isn't a number", value))
+}
+
+// a quiet square wave, interleaved stereo at SAMPLE_RATE
+pub fn stereo_square_wave(seconds: u32) -> Vec<i16> {
+    (0..SAMPLE_RATE * seconds)
+        .flat_map(|i| {
+            let sample = if (i / 50) % 2 == 0 { 1000 } else { -1000 };
+            vec![sample, sample]
+        })
+        .collect()
+}
@@ -0,0 +1,23 @@
+#![cfg(feature = "integration-tests")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::SAMPLE_RATE;
+use libav_frame_encoder::encoder::{encode_silence, AudioArgs};
+
+#[test]
+fn encodes_silence_from_a_filter_source() {
+    let path = common::output_path("silence.m4a");
+    encode_silence(Duration::from_millis(1500), AudioArgs::new(SAMPLE_RATE), path.clone()).expect("encoding failed");
+
+    let probe = common::ffprobe(&path);
+    let stream = &probe["streams"][0];
+    assert_eq!(stream["codec_name"], "aac");
+    assert_eq!(common::json_f64(&stream["sample_rate"]) as u32, SAMPLE_RATE);
+    let duration = common::json_f64(&probe["format"]["duration"]);
+    assert!((duration - 1.5).abs() < 0.1, "duration was {}s", duration);
+
+    std::fs::remove_file(&path).unwrap();
+}
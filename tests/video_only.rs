@@ -0,0 +1,39 @@
+#![cfg(feature = "integration-tests")]
+
+extern crate ffmpeg_next as ffmpeg;
+
+mod common;
+
+use ffmpeg::format::Pixel;
+use libav_frame_encoder::{encoder::{start_thread, VideoArgs}, sink::RetroAVCollector};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+const FPS: u32 = 30;
+const FRAME_COUNT: u64 = 120;
+
+#[test]
+fn encodes_solid_color_frames() {
+    let path = common::output_path("video_only.mp4");
+    let mut collector = RetroAVCollector::new();
+    let encoder = start_thread(collector.sink.output.clone(), path.clone());
+
+    collector.configure_video_only(VideoArgs::new(Pixel::BGRA, FPS, WIDTH, HEIGHT), 0).unwrap();
+    // BGRA, so red is the third byte
+    let red = [0u8, 0, 255, 255].repeat((WIDTH * HEIGHT) as usize);
+    for frame_number in 0..FRAME_COUNT {
+        collector.on_video_refresh(&red, WIDTH, HEIGHT, WIDTH * 4, frame_number).unwrap();
+    }
+    collector.end(FRAME_COUNT).unwrap();
+    encoder.join().expect("encoder thread panicked").expect("encoding failed");
+
+    let probe = common::ffprobe(&path);
+    let stream = &probe["streams"][0];
+    assert_eq!(stream["codec_name"], "h264");
+    assert_eq!(stream["width"], WIDTH);
+    assert_eq!(stream["height"], HEIGHT);
+    let duration = common::json_f64(&probe["format"]["duration"]);
+    assert!((duration - FRAME_COUNT as f64 / FPS as f64).abs() < 0.1, "duration was {}s", duration);
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,44 @@
+#![cfg(all(feature = "integration-tests", feature = "vp9-vpx"))]
+
+extern crate ffmpeg_next as ffmpeg;
+
+mod common;
+
+use ffmpeg::format::Pixel;
+use libav_frame_encoder::{encoder::{start_thread, VideoArgs}, sink::RetroAVCollector};
+
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 360;
+const FPS: u32 = 30;
+const FRAME_COUNT: u64 = 60;
+
+#[test]
+fn encodes_yuv420p_frames_to_webm() {
+    let path = common::output_path("vp9.webm");
+    let mut collector = RetroAVCollector::new();
+    let encoder = start_thread(collector.sink.output.clone(), path.clone());
+
+    let mut args = VideoArgs::new(Pixel::YUV420P, FPS, WIDTH, HEIGHT);
+    args.vp9_tile_columns = Some(1);
+    args.vp9_tile_rows = Some(0);
+    args.vp9_row_mt = true;
+    collector.configure_video_only(args, 0).unwrap();
+    // a full size Y plane followed by quarter size U and V planes, mid grey
+    let luma = (WIDTH * HEIGHT) as usize;
+    let grey = vec![128u8; luma + luma / 2];
+    for frame_number in 0..FRAME_COUNT {
+        collector.on_video_refresh(&grey, WIDTH, HEIGHT, WIDTH, frame_number).unwrap();
+    }
+    collector.end(FRAME_COUNT).unwrap();
+    encoder.join().expect("encoder thread panicked").expect("encoding failed");
+
+    let probe = common::ffprobe(&path);
+    assert!(probe["format"]["format_name"].as_str().unwrap().contains("webm"));
+    let stream = &probe["streams"][0];
+    assert_eq!(stream["codec_name"], "vp9");
+    assert_eq!(stream["pix_fmt"], "yuv420p");
+    assert_eq!(stream["width"], WIDTH);
+    assert_eq!(stream["height"], HEIGHT);
+
+    std::fs::remove_file(&path).unwrap();
+}